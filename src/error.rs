@@ -21,6 +21,7 @@ use std::io;
 pub(crate) enum Error {
     IOError(io::Error),
     UnableToCreateConfigDir,
+    ThemeParseError(String),
 }
 
 impl fmt::Display for Error {
@@ -28,6 +29,7 @@ impl fmt::Display for Error {
         match self {
             Error::IOError(e) => write!(f, "io error: {}", e),
             Error::UnableToCreateConfigDir => write!(f, "Unable to create config dir"),
+            Error::ThemeParseError(reason) => write!(f, "could not parse theme file: {}", reason),
         }
     }
 }
@@ -3,6 +3,9 @@
 pub mod args;
 pub mod error;
 pub mod logger;
+pub mod renderer;
+mod scheme;
+pub mod strings;
 pub mod theming;
 mod xdg;
 
@@ -10,14 +13,17 @@ use crate::args::parse_args;
 use crate::logger::LogItem;
 use crate::theming::{curses_attr, parse_attrs};
 
-use chrono::{Datelike, Timelike};
+use chrono::{Datelike, NaiveDate, TimeZone, Timelike, Utc};
 use pyo3::{
     conversion::{IntoPy, ToPyObject},
     create_exception,
-    exceptions::PyIOError,
+    exceptions::{PyIOError, PyValueError},
     marker::Python,
-    prelude::{pyfunction, pymodule, wrap_pyfunction, PyErr, PyModule, PyObject, PyResult},
-    types::{PyDateTime, PyDict},
+    prelude::{
+        pyclass, pyfunction, pymethods, pymodule, wrap_pyfunction, PyErr, PyModule, PyObject,
+        PyRef, PyRefMut, PyResult,
+    },
+    types::{PyDateTime, PyDateAccess, PyDict, PyTimeAccess},
 };
 
 create_exception!(libpoezio, LogParseError, pyo3::exceptions::PyException);
@@ -27,7 +33,11 @@ fn libpoezio(py: Python, m: &PyModule) -> PyResult<()> {
     m.add("LogParseError", py.get_type::<LogParseError>())?;
     m.add_function(wrap_pyfunction!(to_curses_attr, m)?)?;
     m.add_function(wrap_pyfunction!(parse_logs, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_logs_lossy, m)?)?;
+    m.add_function(wrap_pyfunction!(write_logs, m)?)?;
     m.add_function(wrap_pyfunction!(run_cmdline_args, m)?)?;
+    m.add_class::<PyLogReader>()?;
+    m.add_class::<PyColourScheme>()?;
     m.add("XDG", xdg::PyProject::new(xdg::PROJECT.clone()))?;
 
     Ok(())
@@ -68,6 +78,25 @@ fn chrono_to_datetime(py: Python, chrono: &chrono::DateTime<chrono::Utc>) -> PyR
     Ok(datetime.to_object(py))
 }
 
+fn item_to_dict<'p>(py: Python<'p>, item: logger::Item) -> PyResult<&'p PyDict> {
+    let dict = PyDict::new(py);
+    let (time, txt) = match item {
+        logger::Item::Message(message) => {
+            let time = chrono_to_datetime(py, message.get_time())?;
+            dict.set_item("nickname", message.get_nick())?;
+            (time, message.get_message())
+        }
+        logger::Item::Info(info) => {
+            let time = chrono_to_datetime(py, info.get_time())?;
+            (time, info.get_message())
+        }
+    };
+    dict.set_item("history", true)?;
+    dict.set_item("time", time)?;
+    dict.set_item("txt", txt)?;
+    Ok(dict)
+}
+
 #[pyfunction]
 fn parse_logs(py: Python, input: &str) -> PyResult<PyObject> {
     let logs = match logger::parse_logs(input) {
@@ -76,24 +105,156 @@ fn parse_logs(py: Python, input: &str) -> PyResult<PyObject> {
     };
     let mut items = Vec::new();
     for item in logs {
+        items.push(item_to_dict(py, item)?);
+    }
+    Ok(items.into_py(py).to_object(py))
+}
+
+#[pyfunction]
+fn parse_logs_lossy(py: Python, input: &str) -> PyResult<(PyObject, PyObject)> {
+    let (logs, problems) = logger::parse_logs_lossy(input);
+    let mut items = Vec::new();
+    for item in logs {
+        items.push(item_to_dict(py, item)?);
+    }
+    let mut problem_dicts = Vec::new();
+    for problem in problems {
         let dict = PyDict::new(py);
-        let (time, txt) = match item {
-            logger::Item::Message(message) => {
-                let time = chrono_to_datetime(py, message.get_time())?;
-                dict.set_item("nickname", message.get_nick())?;
-                (time, message.get_message())
+        dict.set_item("offset", problem.offset)?;
+        dict.set_item("reason", problem.reason)?;
+        problem_dicts.push(dict);
+    }
+    Ok((
+        items.into_py(py).to_object(py),
+        problem_dicts.into_py(py).to_object(py),
+    ))
+}
+
+/// Python-facing iterator over the [`logger::LogReader`] parse of a log
+/// string, yielding the same dicts as `parse_logs` one at a time instead of
+/// building the whole list up front.
+#[pyclass(name = "LogReader")]
+struct PyLogReader {
+    buffer: String,
+    offset: usize,
+}
+
+#[pymethods]
+impl PyLogReader {
+    #[new]
+    fn new(input: String) -> Self {
+        PyLogReader { buffer: input, offset: 0 }
+    }
+
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<Option<PyObject>> {
+        let (dict, consumed) = {
+            if slf.offset >= slf.buffer.len() {
+                return Ok(None);
             }
-            logger::Item::Info(info) => {
-                let time = chrono_to_datetime(py, info.get_time())?;
-                (time, info.get_message())
+            let remaining = &slf.buffer[slf.offset..];
+            let mut reader = logger::LogReader::new(remaining);
+            match reader.next() {
+                None => return Ok(None),
+                Some(Err(err)) => return Err(nom_to_py_err(py, err)),
+                Some(Ok(item)) => {
+                    let consumed = remaining.len() - reader.remaining().len();
+                    (item_to_dict(py, item)?, consumed)
+                }
             }
         };
-        dict.set_item("history", true)?;
-        dict.set_item("time", time)?;
-        dict.set_item("txt", txt)?;
-        items.push(dict);
+        slf.offset += consumed;
+        Ok(Some(dict.into_py(py)))
     }
-    Ok(items.into_py(py).to_object(py))
+}
+
+/// Python-facing handle on a [`scheme::ColourScheme`]: resolves named
+/// styles (`$highlight`, `$nick`, ...) from a `themes/<name>.theme` file in
+/// the XDG config dir to ready-to-use curses attrs. Calling
+/// `reload_if_changed` re-parses the file if it changed on disk, without
+/// requiring a restart; lookups alone do not check the file.
+#[pyclass(name = "ColourScheme")]
+struct PyColourScheme {
+    scheme: scheme::ColourScheme,
+}
+
+#[pymethods]
+impl PyColourScheme {
+    #[new]
+    fn new(name: String) -> PyResult<Self> {
+        let scheme = scheme::ColourScheme::load(&name)
+            .map_err(|err| PyIOError::new_err(err.to_string()))?;
+        Ok(PyColourScheme { scheme })
+    }
+
+    /// Re-parses the theme file if it changed on disk since the last load.
+    /// Returns whether a reload happened.
+    fn reload_if_changed(&mut self) -> PyResult<bool> {
+        self.scheme
+            .reload_if_changed()
+            .map_err(|err| PyIOError::new_err(err.to_string()))
+    }
+
+    /// Returns the curses attr for a named style (e.g. `"highlight"` for
+    /// `$highlight`), or `None` if the theme file has no such key.
+    fn get_curses_attr(&self, py: Python, key: &str) -> PyResult<Option<PyObject>> {
+        Ok(self
+            .scheme
+            .get(key)
+            .map(|(fg, bg, attrs)| py_object!(py, curses_attr((fg, bg), attrs))))
+    }
+}
+
+fn datetime_to_chrono(datetime: &PyDateTime) -> PyResult<chrono::DateTime<Utc>> {
+    NaiveDate::from_ymd_opt(
+        datetime.get_year(),
+        datetime.get_month() as u32,
+        datetime.get_day() as u32,
+    )
+    .and_then(|date| {
+        date.and_hms_opt(
+            datetime.get_hour() as u32,
+            datetime.get_minute() as u32,
+            datetime.get_second() as u32,
+        )
+    })
+    .map(|naive| Utc.from_utc_datetime(&naive))
+    .ok_or_else(|| PyValueError::new_err("datetime out of range"))
+}
+
+#[pyfunction]
+fn write_logs(items: Vec<&PyDict>) -> PyResult<String> {
+    let mut owned = Vec::new();
+    for dict in items {
+        let time_obj = dict.get_item("time").ok_or_else(|| {
+            pyo3::exceptions::PyKeyError::new_err("missing \"time\" key")
+        })?;
+        let datetime: &PyDateTime = time_obj.downcast()?;
+        let time = datetime_to_chrono(datetime)?;
+        let nickname: Option<String> = match dict.get_item("nickname") {
+            Some(nickname) => Some(nickname.extract()?),
+            None => None,
+        };
+        let txt: String = dict
+            .get_item("txt")
+            .ok_or_else(|| pyo3::exceptions::PyKeyError::new_err("missing \"txt\" key"))?
+            .extract()?;
+        owned.push((time, nickname, txt));
+    }
+    let logs: Vec<logger::Item> = owned
+        .iter()
+        .map(|(time, nickname, txt)| {
+            let message: Vec<&str> = txt.split('\n').collect();
+            match nickname {
+                Some(nick) => logger::Item::Message(logger::LogMessage::new(*time, nick, message)),
+                None => logger::Item::Info(logger::LogInfo::new(*time, message)),
+            }
+        })
+        .collect();
+    Ok(logger::write_logs(&logs))
 }
 
 #[pyfunction]
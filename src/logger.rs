@@ -1,11 +1,13 @@
 use std::str::FromStr;
-use chrono::{DateTime, Utc, TimeZone};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
 use nom;
 use nom::{
     IResult,
-    sequence::tuple,
+    branch::alt,
+    character::complete::digit1,
+    sequence::{preceded, tuple},
     bytes::complete::{tag, take, take_until},
-    combinator::{map, map_res},
+    combinator::{map, map_res, opt},
     multi::many_m_n,
 };
 
@@ -20,6 +22,12 @@ pub struct LogInfo<'a> {
     message: Vec<&'a str>,
 }
 
+impl<'a> LogInfo<'a> {
+    pub fn new(time: DateTime<Utc>, message: Vec<&'a str>) -> Self {
+        LogInfo { time, message }
+    }
+}
+
 impl<'a> LogItem for LogInfo<'a> {
     fn get_time(&self) -> &DateTime<Utc> {
         &self.time
@@ -38,6 +46,10 @@ pub struct LogMessage<'a> {
 }
 
 impl<'a> LogMessage<'a> {
+    pub fn new(time: DateTime<Utc>, nick: &'a str, message: Vec<&'a str>) -> Self {
+        LogMessage { time, nick, message }
+    }
+
     pub fn get_nick(&self) -> &str {
         self.nick
     }
@@ -53,8 +65,57 @@ impl<'a> LogItem for LogMessage<'a> {
     }
 }
 
+/// Turn a `.NNN` fractional-seconds suffix (already stripped of its leading
+/// dot) into nanoseconds, truncating or zero-padding to 9 digits.
+fn frac_to_nanos(frac: &str) -> u32 {
+    let mut digits: String = frac.chars().take(9).collect();
+    while digits.len() < 9 {
+        digits.push('0');
+    }
+    digits.parse().unwrap_or(0)
+}
+
+fn parse_offset_seconds(i: &str) -> IResult<&str, i32> {
+    alt((
+        map(tag("Z"), |_| 0i32),
+        map(
+            tuple((
+                alt((tag("+"), tag("-"))),
+                map_res(take(2usize), i32::from_str),
+                opt(tag(":")),
+                map_res(take(2usize), i32::from_str),
+            )),
+            |(sign, hours, _, minutes)| {
+                let total = hours * 3600 + minutes * 60;
+                if sign == "-" { -total } else { total }
+            },
+        ),
+    ))(i)
+}
+
+fn build_datetime(
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    offset_seconds: i32,
+) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    let time = NaiveTime::from_hms_nano_opt(hour, minute, second, nanos)?;
+    let offset = FixedOffset::east_opt(offset_seconds)?;
+    let local = offset.from_local_datetime(&date.and_time(time)).single()?;
+    Some(local.with_timezone(&Utc))
+}
+
+/// Parse an ISO-8601-ish timestamp: `YYYYMMDDTHH:MM:SS`, an optional
+/// `.NNN` fractional-seconds suffix, and either a trailing `Z` or a
+/// `+HHMM`/`+HH:MM` (or `-`) numeric UTC offset. The result is normalized to
+/// `Utc`.
 pub fn parse_datetime(i: &str) -> IResult<&str, DateTime<Utc>> {
-    let (i, (year, month, day, _, hour, _, minute, _, second, _)) = tuple((
+    let (i, (year, month, day, _, hour, _, minute, _, second, frac, offset_seconds)) = tuple((
         map_res(take(4usize), i32::from_str),
         map_res(take(2usize), u32::from_str),
         map_res(take(2usize), u32::from_str),
@@ -64,9 +125,13 @@ pub fn parse_datetime(i: &str) -> IResult<&str, DateTime<Utc>> {
         map_res(take(2usize), u32::from_str),
         tag(":"),
         map_res(take(2usize), u32::from_str),
-        tag("Z"),
+        opt(preceded(tag("."), digit1)),
+        parse_offset_seconds,
     ))(i)?;
-    Ok((i, Utc.ymd(year, month, day).and_hms(hour, minute, second)))
+    let nanos = frac.map(frac_to_nanos).unwrap_or(0);
+    let datetime = build_datetime(year, month, day, hour, minute, second, nanos, offset_seconds)
+        .ok_or_else(|| nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Verify)))?;
+    Ok((i, datetime))
 }
 
 pub fn parse_log_info(i: &str) -> IResult<&str, LogInfo> {
@@ -121,6 +186,54 @@ pub enum Item<'a> {
     Info(LogInfo<'a>),
 }
 
+fn write_lines(message: &[&str]) -> String {
+    let mut out = String::new();
+    for line in message {
+        out.push(' ');
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Serialize a single [`Item`] back into its on-disk `MR`/`MI` representation.
+///
+/// This is the inverse of [`parse_log_message`]/[`parse_log_info`]: the
+/// output re-parses to an equal `Item`.
+pub fn write_item(item: &Item) -> String {
+    match item {
+        Item::Message(message) => {
+            let nb_lines = message.message.len() - 1;
+            let mut out = format!(
+                "MR {} {:03} <{}>  {}\n",
+                message.time.format("%Y%m%dT%H:%M:%S%.fZ"),
+                nb_lines,
+                message.nick,
+                message.message[0],
+            );
+            out.push_str(&write_lines(&message.message[1..]));
+            out
+        }
+        Item::Info(info) => {
+            let nb_lines = info.message.len() - 1;
+            let mut out = format!(
+                "MI {} {:03} {}\n",
+                info.time.format("%Y%m%dT%H:%M:%S%.fZ"),
+                nb_lines,
+                info.message[0],
+            );
+            out.push_str(&write_lines(&info.message[1..]));
+            out
+        }
+    }
+}
+
+/// Serialize a whole log back into its on-disk representation, the inverse
+/// of [`parse_logs`].
+pub fn write_logs(items: &[Item]) -> String {
+    items.iter().map(write_item).collect()
+}
+
 pub fn parse_logs(mut logs: &str) -> IResult<&str, Vec<Item>> {
     let mut items = vec![];
     loop {
@@ -142,10 +255,132 @@ pub fn parse_logs(mut logs: &str) -> IResult<&str, Vec<Item>> {
     Ok((logs, items))
 }
 
+/// A record that [`parse_logs_lossy`] couldn't parse, along with where it
+/// was found.
+#[derive(Debug, PartialEq)]
+pub struct ParseProblem {
+    /// Byte offset of the offending line in the original input.
+    pub offset: usize,
+    /// Short human-readable explanation of what went wrong.
+    pub reason: String,
+}
+
+fn skip_line(logs: &str) -> &str {
+    let line_end = logs.find('\n').map(|i| i + 1).unwrap_or_else(|| logs.len());
+    &logs[line_end..]
+}
+
+/// Lenient variant of [`parse_logs`]: instead of bailing out on the first
+/// line that isn't a valid `MR `/`MI ` record, record a [`ParseProblem`] and
+/// resynchronize on the next line that starts a valid record, so a single
+/// corrupt line doesn't discard the rest of the file.
+pub fn parse_logs_lossy(input: &str) -> (Vec<Item>, Vec<ParseProblem>) {
+    let mut items = vec![];
+    let mut problems = vec![];
+    let mut logs = input;
+    while !logs.is_empty() {
+        let parsed = if logs.starts_with("MR ") {
+            parse_log_message(logs).map(|(rest, message)| (rest, Item::Message(message)))
+        } else if logs.starts_with("MI ") {
+            parse_log_info(logs).map(|(rest, info)| (rest, Item::Info(info)))
+        } else {
+            Err(nom::Err::Error(nom::error::Error::new(logs, nom::error::ErrorKind::Fail)))
+        };
+        match parsed {
+            Ok((rest, item)) => {
+                items.push(item);
+                logs = rest;
+            }
+            Err(_) => {
+                let offset = input.len() - logs.len();
+                let bad_line = logs[..logs.find('\n').map(|i| i + 1).unwrap_or_else(|| logs.len())]
+                    .trim_end_matches('\n');
+                problems.push(ParseProblem {
+                    offset,
+                    reason: format!("not a valid MR/MI record: {:?}", bad_line),
+                });
+                logs = skip_line(logs);
+                while !logs.is_empty() && !logs.starts_with("MR ") && !logs.starts_with("MI ") {
+                    logs = skip_line(logs);
+                }
+            }
+        }
+    }
+    (items, problems)
+}
+
+/// Incremental, allocation-light parser for `MR `/`MI ` logs.
+///
+/// Unlike [`parse_logs`], which materializes a `Vec<Item>` for the whole
+/// input up front, `LogReader` parses and yields one [`Item`] per call to
+/// `next()`, so a multi-megabyte history file can be paged through without
+/// building every parsed item at once. The input itself is borrowed whole,
+/// as a single `&str`. Iteration stops (yielding
+/// `None`) after the first parse error.
+pub struct LogReader<'a> {
+    remaining: &'a str,
+    failed: bool,
+}
+
+impl<'a> LogReader<'a> {
+    pub fn new(input: &'a str) -> Self {
+        LogReader { remaining: input, failed: false }
+    }
+
+    /// The as-yet-unparsed tail of the input.
+    pub fn remaining(&self) -> &'a str {
+        self.remaining
+    }
+}
+
+impl<'a> Iterator for LogReader<'a> {
+    type Item = Result<Item<'a>, nom::Err<nom::error::Error<&'a str>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.remaining.is_empty() {
+            return None;
+        }
+        let result = if self.remaining.starts_with("MR ") {
+            parse_log_message(self.remaining).map(|(rest, message)| (rest, Item::Message(message)))
+        } else if self.remaining.starts_with("MI ") {
+            parse_log_info(self.remaining).map(|(rest, info)| (rest, Item::Info(info)))
+        } else {
+            Err(nom::Err::Error(nom::error::Error::new(self.remaining, nom::error::ErrorKind::Fail)))
+        };
+        match result {
+            Ok((rest, item)) => {
+                self.remaining = rest;
+                Some(Ok(item))
+            }
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn datetime_with_numeric_offset() {
+        let (_, time) = parse_datetime("20181016T16:10:08+0200").unwrap();
+        assert_eq!(time, "2018-10-16T16:10:08+0200".parse::<DateTime<Utc>>().unwrap());
+    }
+
+    #[test]
+    fn datetime_with_fractional_seconds() {
+        let (_, time) = parse_datetime("20181016T14:10:08.123Z").unwrap();
+        assert_eq!(time.timestamp_subsec_millis(), 123);
+    }
+
+    #[test]
+    fn datetime_out_of_range_month() {
+        parse_datetime("20181316T14:10:08Z").unwrap_err();
+    }
+
     #[test]
     fn simple_message() {
         let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello world!\n";
@@ -203,6 +438,67 @@ mod tests {
         parse_logs(log).unwrap_err();
     }
 
+    #[test]
+    fn lossy_trailing_characters() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello…\nMR 20181016T14:10:11Z 000 <Link Mauve>  world!\n\n";
+        let (items, problems) = parse_logs_lossy(log);
+        assert_eq!(items.len(), 2);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].offset, log.len() - 1);
+    }
+
+    #[test]
+    fn lossy_skips_corrupt_line_in_the_middle() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello…\ngarbage line\nMR 20181016T14:10:11Z 000 <Link Mauve>  world!\n";
+        let (items, problems) = parse_logs_lossy(log);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            items,
+            vec![
+                Item::Message(LogMessage {
+                    time: "2018-10-16T16:10:08+0200".parse().unwrap(),
+                    nick: "Link Mauve",
+                    message: vec!["Hello…"],
+                }),
+                Item::Message(LogMessage {
+                    time: "2018-10-16T16:10:11+0200".parse().unwrap(),
+                    nick: "Link Mauve",
+                    message: vec!["world!"],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn log_reader_yields_each_item() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello…\nMR 20181016T14:10:11Z 000 <Link Mauve>  world!\n";
+        let items: Vec<Item> = LogReader::new(log).map(|item| item.unwrap()).collect();
+        assert_eq!(
+            items,
+            vec![
+                Item::Message(LogMessage {
+                    time: "2018-10-16T16:10:08+0200".parse().unwrap(),
+                    nick: "Link Mauve",
+                    message: vec!["Hello…"],
+                }),
+                Item::Message(LogMessage {
+                    time: "2018-10-16T16:10:11+0200".parse().unwrap(),
+                    nick: "Link Mauve",
+                    message: vec!["world!"],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn log_reader_stops_after_error() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello world!\n\n";
+        let mut reader = LogReader::new(log);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
     #[test]
     fn multiline_message() {
         let log = "MR 20181016T14:10:08Z 001 <Link Mauve>  Hello…\n world!\n";
@@ -214,4 +510,35 @@ mod tests {
         let (_, message2) = parse_log_message(log).unwrap();
         assert_eq!(message, message2);
     }
+
+    fn assert_round_trips(log: &str) {
+        let (_, items) = parse_logs(log).unwrap();
+        let written = write_logs(&items);
+        let (_, items2) = parse_logs(&written).unwrap();
+        assert_eq!(items, items2);
+    }
+
+    #[test]
+    fn write_simple_message() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello world!\n";
+        assert_round_trips(log);
+    }
+
+    #[test]
+    fn write_multiple_messages() {
+        let log = "MR 20181016T14:10:08Z 000 <Link Mauve>  Hello…\nMR 20181016T14:10:11Z 000 <Link Mauve>  world!\n";
+        assert_round_trips(log);
+    }
+
+    #[test]
+    fn write_multiline_message() {
+        let log = "MR 20181016T14:10:08Z 001 <Link Mauve>  Hello…\n world!\n";
+        assert_round_trips(log);
+    }
+
+    #[test]
+    fn write_info() {
+        let log = "MI 20181016T14:10:08Z 000 Link Mauve has joined\n";
+        assert_round_trips(log);
+    }
 }
@@ -0,0 +1,230 @@
+use std::io::Write;
+use enum_set::EnumSet;
+
+use crate::theming::{curses_attr, rgb_curses_attr, Attr};
+
+/// Abstracts the operations needed to draw a parsed `Vec<Item>` (see
+/// `strings.rs`), so the same markup can be pushed to an ncurses window or
+/// to any other sink (a plain file, a pipe, a non-curses pane) without the
+/// parser or the `Item` tree knowing which.
+pub trait Renderer {
+    /// Equivalent of `\x19o`: clear every attribute and color back to the
+    /// terminal default.
+    fn set_default(&mut self);
+    /// Turn a single attribute on, in addition to whatever is already active.
+    fn enable_attr(&mut self, attr: Attr);
+    /// Turn a single attribute off, restoring the rest of the active state.
+    fn disable_attr(&mut self, attr: Attr);
+    /// Set the active color pair. `attrs` overrides the tracked attribute
+    /// set when given (`\x19fg,bg,attrs}`); pass `None` to leave the
+    /// currently active attributes untouched (`\x19fg,bg}`).
+    fn set_colour(&mut self, fg: i16, bg: i16, attrs: Option<EnumSet<Attr>>);
+    /// Set the active color pair from an exact RGB triple (`\x19#rrggbb}`).
+    fn set_rgb_colour(&mut self, fg: (u8, u8, u8), bg: Option<(u8, u8, u8)>);
+    /// Emit literal text using whatever attributes/colors are active.
+    fn write_text(&mut self, text: &str);
+}
+
+/// Tracks the attributes and color pair currently active while a `Vec<Item>`
+/// is being rendered, so that turning a single attribute off (`Item::AttrOff`)
+/// can restore the rest of the active state instead of resetting everything
+/// like `\x19o` does.
+#[derive(Clone, Copy)]
+struct RenderState {
+    attrs: EnumSet<Attr>,
+    colours: (i16, i16),
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        RenderState { attrs: EnumSet::new(), colours: (-1, -1) }
+    }
+}
+
+/// Renders a `Vec<Item>` onto an ncurses `WINDOW`.
+pub struct CursesRenderer {
+    window: ncurses::WINDOW,
+    state: RenderState,
+}
+
+impl CursesRenderer {
+    pub fn new(window: ncurses::WINDOW) -> Self {
+        CursesRenderer { window, state: RenderState::default() }
+    }
+}
+
+impl Renderer for CursesRenderer {
+    fn set_default(&mut self) {
+        ncurses::wattrset(self.window, 0);
+        self.state = RenderState::default();
+    }
+
+    fn enable_attr(&mut self, attr: Attr) {
+        self.state.attrs.insert(attr);
+        ncurses::wattron(self.window, attr.get_attron());
+    }
+
+    fn disable_attr(&mut self, attr: Attr) {
+        self.state.attrs.remove(attr);
+        ncurses::wattroff(self.window, attr.get_attron());
+        ncurses::wattron(self.window, curses_attr(self.state.colours, self.state.attrs));
+    }
+
+    fn set_colour(&mut self, fg: i16, bg: i16, attrs: Option<EnumSet<Attr>>) {
+        self.state.colours = (fg, bg);
+        if let Some(attrs) = attrs {
+            // `wattron` only ever adds bits, so any attr that was active
+            // before this override but isn't in the new set has to be
+            // turned off explicitly, or it would stay stuck on in ncurses
+            // while `self.state` believes it's gone.
+            for attr in self.state.attrs.iter() {
+                if !attrs.contains(attr) {
+                    ncurses::wattroff(self.window, attr.get_attron());
+                }
+            }
+            self.state.attrs = attrs;
+        }
+        ncurses::wattron(self.window, curses_attr(self.state.colours, self.state.attrs));
+    }
+
+    fn set_rgb_colour(&mut self, fg: (u8, u8, u8), bg: Option<(u8, u8, u8)>) {
+        let (colours, pair) = rgb_curses_attr(fg, bg);
+        self.state.colours = colours;
+        ncurses::wattron(self.window, pair);
+    }
+
+    fn write_text(&mut self, text: &str) {
+        ncurses::waddstr(self.window, text);
+    }
+}
+
+fn attr_sgr_on(attr: Attr) -> &'static str {
+    match attr {
+        Attr::Bold => "1",
+        Attr::Italic => "3",
+        Attr::Underline => "4",
+        Attr::Blink => "5",
+    }
+}
+
+fn attr_sgr_off(attr: Attr) -> &'static str {
+    match attr {
+        Attr::Bold => "22",
+        Attr::Italic => "23",
+        Attr::Underline => "24",
+        Attr::Blink => "25",
+    }
+}
+
+/// Renders a `Vec<Item>` as ANSI SGR escape sequences written to any
+/// `io::Write`, e.g. a log file or a pipe into `less -R`. IO errors are
+/// swallowed the same way the ncurses calls in [`CursesRenderer`] ignore
+/// their return codes.
+pub struct AnsiRenderer<W: Write> {
+    writer: W,
+    state: RenderState,
+}
+
+impl<W: Write> AnsiRenderer<W> {
+    pub fn new(writer: W) -> Self {
+        AnsiRenderer { writer, state: RenderState::default() }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Renderer for AnsiRenderer<W> {
+    fn set_default(&mut self) {
+        self.state = RenderState::default();
+        let _ = write!(self.writer, "\x1b[0m");
+    }
+
+    fn enable_attr(&mut self, attr: Attr) {
+        self.state.attrs.insert(attr);
+        let _ = write!(self.writer, "\x1b[{}m", attr_sgr_on(attr));
+    }
+
+    fn disable_attr(&mut self, attr: Attr) {
+        self.state.attrs.remove(attr);
+        let _ = write!(self.writer, "\x1b[{}m", attr_sgr_off(attr));
+    }
+
+    fn set_colour(&mut self, fg: i16, bg: i16, attrs: Option<EnumSet<Attr>>) {
+        self.state.colours = (fg, bg);
+        if let Some(attrs) = attrs {
+            for attr in self.state.attrs.iter() {
+                if !attrs.contains(attr) {
+                    let _ = write!(self.writer, "\x1b[{}m", attr_sgr_off(attr));
+                }
+            }
+            for attr in attrs.iter() {
+                let _ = write!(self.writer, "\x1b[{}m", attr_sgr_on(attr));
+            }
+            self.state.attrs = attrs;
+        }
+        if fg >= 0 {
+            let _ = write!(self.writer, "\x1b[38;5;{}m", fg);
+        }
+        if bg >= 0 {
+            let _ = write!(self.writer, "\x1b[48;5;{}m", bg);
+        }
+    }
+
+    fn set_rgb_colour(&mut self, fg: (u8, u8, u8), bg: Option<(u8, u8, u8)>) {
+        let _ = write!(self.writer, "\x1b[38;2;{};{};{}m", fg.0, fg.1, fg.2);
+        if let Some(bg) = bg {
+            let _ = write!(self.writer, "\x1b[48;2;{};{};{}m", bg.0, bg.1, bg.2);
+        }
+    }
+
+    fn write_text(&mut self, text: &str) {
+        let _ = self.writer.write_all(text.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strings::Item;
+
+    #[test]
+    fn ansi_renders_bold_and_colour() {
+        let mut renderer = AnsiRenderer::new(Vec::new());
+        Item::AttrOn(Attr::Bold).render(&mut renderer);
+        Item::ColourOn(1, -1).render(&mut renderer);
+        Item::Text("hi").render(&mut renderer);
+        Item::AttrOff(Attr::Bold).render(&mut renderer);
+        let out = String::from_utf8(renderer.into_inner()).unwrap();
+        assert_eq!(out, "\x1b[1m\x1b[38;5;1mhi\x1b[22m");
+    }
+
+    #[test]
+    fn ansi_renders_rgb_colour() {
+        let mut renderer = AnsiRenderer::new(Vec::new());
+        Item::RgbColourOn(0xff, 0x80, 0x00, Some((0, 0, 0))).render(&mut renderer);
+        let out = String::from_utf8(renderer.into_inner()).unwrap();
+        assert_eq!(out, "\x1b[38;2;255;128;0m\x1b[48;2;0;0;0m");
+    }
+
+    #[test]
+    fn ansi_reset_clears_state() {
+        let mut renderer = AnsiRenderer::new(Vec::new());
+        Item::AttrSet0.render(&mut renderer);
+        let out = String::from_utf8(renderer.into_inner()).unwrap();
+        assert_eq!(out, "\x1b[0m");
+    }
+
+    #[test]
+    fn ansi_attr_on_ex_overrides_attrs() {
+        let mut renderer = AnsiRenderer::new(Vec::new());
+        Item::AttrOn(Attr::Bold).render(&mut renderer);
+        let mut attrs = EnumSet::new();
+        attrs.insert(Attr::Underline);
+        Item::AttrOnEx(33, 41, attrs).render(&mut renderer);
+        let out = String::from_utf8(renderer.into_inner()).unwrap();
+        // Bold (no longer in the overriding set) is turned off, Underline on.
+        assert_eq!(out, "\x1b[1m\x1b[22m\x1b[4m\x1b[38;5;33m\x1b[48;5;41m");
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use enum_set::EnumSet;
+
+use crate::error::Error;
+use crate::theming::{parse_attrs, Attr};
+use crate::xdg::PROJECT;
+
+/// A single named style: an indexed foreground/background color pair plus
+/// the attributes layered on top of it, e.g. what `$highlight` resolves to.
+pub(crate) type Style = (i16, i16, EnumSet<Attr>);
+
+named!(
+    pub(crate) parse_colour_spec<&str, Style>,
+    do_parse!(
+        fg: map_res!(take_till1!(|c| c == ','), i16::from_str) >>
+        bg: opt!(preceded!(tag!(","), map_res!(take_till1!(|c| c == ','), i16::from_str))) >>
+        attrs: opt!(preceded!(tag!(","), call!(parse_attrs))) >>
+        ((fg, bg.unwrap_or(-1), attrs.unwrap_or_else(EnumSet::new)))
+    )
+);
+
+fn parse_theme_line(line: &str) -> Result<(String, Style), Error> {
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| Error::ThemeParseError(format!("missing '=' in line {:?}", line)))?;
+    let value = value.trim();
+    let (_, style) = parse_colour_spec(value)
+        .map_err(|err| Error::ThemeParseError(format!("{:?}: {}", value, err)))?;
+    Ok((key.trim().to_string(), style))
+}
+
+/// A table of semantic style names (`$highlight`, `$nick`, ...) loaded from
+/// `themes/<name>.theme` in the XDG config dir, so UI code can look up a
+/// name instead of a hardcoded color pair. The `\x19` markup parser in
+/// `strings.rs` does not resolve these names. Call
+/// [`ColourScheme::reload_if_changed`] to pick up edits without restarting.
+pub(crate) struct ColourScheme {
+    path: PathBuf,
+    loaded_at: Option<SystemTime>,
+    styles: HashMap<String, Style>,
+}
+
+impl ColourScheme {
+    /// Loads `themes/<name>.theme` from the XDG config dir.
+    pub(crate) fn load(name: &str) -> Result<Self, Error> {
+        let path = PROJECT.config_dir().join("themes").join(format!("{}.theme", name));
+        let mut scheme = ColourScheme {
+            path,
+            loaded_at: None,
+            styles: HashMap::new(),
+        };
+        scheme.reload()?;
+        Ok(scheme)
+    }
+
+    fn parse(input: &str) -> Result<HashMap<String, Style>, Error> {
+        let mut styles = HashMap::new();
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, style) = parse_theme_line(line)?;
+            styles.insert(key, style);
+        }
+        Ok(styles)
+    }
+
+    fn reload(&mut self) -> Result<(), Error> {
+        let contents = fs::read_to_string(&self.path)?;
+        self.styles = Self::parse(&contents)?;
+        self.loaded_at = fs::metadata(&self.path)?.modified().ok();
+        Ok(())
+    }
+
+    /// Re-parses the theme file if its mtime changed since the last load,
+    /// returning whether a reload happened.
+    pub(crate) fn reload_if_changed(&mut self) -> Result<bool, Error> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified.is_some() && modified != self.loaded_at {
+            self.reload()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Looks up a named style, e.g. `scheme.get("highlight")` for `$highlight`.
+    pub(crate) fn get(&self, key: &str) -> Option<Style> {
+        self.styles.get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colour_only_spec() {
+        assert_eq!(parse_colour_spec("3,-1").unwrap().1, (3, -1, EnumSet::new()));
+    }
+
+    #[test]
+    fn parses_colour_with_attrs() {
+        let mut expected = EnumSet::new();
+        expected.insert(Attr::Bold);
+        assert_eq!(parse_colour_spec("3,-1,b").unwrap().1, (3, -1, expected));
+    }
+
+    #[test]
+    fn parses_theme_line() {
+        let mut expected = EnumSet::new();
+        expected.insert(Attr::Bold);
+        assert_eq!(
+            parse_theme_line("highlight = 3,-1,b").unwrap(),
+            ("highlight".to_string(), (3, -1, expected))
+        );
+    }
+
+    #[test]
+    fn parses_theme_file_contents() {
+        let contents = "# a comment\nhighlight = 3,-1,b\n\nnick=4,-1\n";
+        let styles = ColourScheme::parse(contents).unwrap();
+        let mut expected_attrs = EnumSet::new();
+        expected_attrs.insert(Attr::Bold);
+        assert_eq!(styles.get("highlight"), Some(&(3, -1, expected_attrs)));
+        assert_eq!(styles.get("nick"), Some(&(4, -1, EnumSet::new())));
+    }
+
+    #[test]
+    fn rejects_line_without_equals() {
+        parse_theme_line("not a valid line").unwrap_err();
+    }
+}
@@ -1,31 +1,51 @@
 use std::str::FromStr;
 use std::mem;
 use enum_set::EnumSet;
-use crate::theming::{Attr, curses_attr, parse_attrs};
-use ncurses::{WINDOW, waddstr, wattrset, wattron, getyx};
+use crate::renderer::Renderer;
+use crate::theming::{Attr, parse_attrs};
+use ncurses::{WINDOW, getyx};
 
 #[derive(Debug, PartialEq)]
 pub enum Item<'a> {
     AttrSet0,
     AttrOn(Attr),
+    AttrOff(Attr),
     ColourOn(i16, i16),
     AttrOnEx(i16, i16, EnumSet<Attr>),
+    RgbColourOn(u8, u8, u8, Option<(u8, u8, u8)>),
     Text(&'a str),
 }
 
 impl<'a> Item<'a> {
-    fn print_window(&self, window: WINDOW) {
-        // TODO: handle wattroff() too, or at least figure out what it breaks not to do it.
+    pub(crate) fn render(&self, renderer: &mut dyn Renderer) {
         match *self {
-            Item::AttrSet0 => wattrset(window, 0),
-            Item::AttrOn(attr) => wattron(window, attr.get_attron()),
-            Item::ColourOn(fg, bg) => wattron(window, curses_attr(fg, bg, EnumSet::new())),
-            Item::AttrOnEx(fg, bg, attrs) => wattron(window, curses_attr(fg, bg, attrs)),
-            Item::Text(text) => waddstr(window, text),
+            Item::AttrSet0 => renderer.set_default(),
+            Item::AttrOn(attr) => renderer.enable_attr(attr),
+            Item::AttrOff(attr) => renderer.disable_attr(attr),
+            Item::ColourOn(fg, bg) => renderer.set_colour(fg, bg, None),
+            Item::AttrOnEx(fg, bg, attrs) => renderer.set_colour(fg, bg, Some(attrs)),
+            Item::RgbColourOn(r, g, b, bg) => renderer.set_rgb_colour((r, g, b), bg),
+            Item::Text(text) => renderer.write_text(text),
         };
     }
 }
 
+named!(
+    hex_byte<&str, u8>,
+    map_res!(take!(2), |s: &str| u8::from_str_radix(s, 16))
+);
+
+named!(
+    hex_colour<&str, (u8, u8, u8)>,
+    do_parse!(
+        tag!("#") >>
+        r: call!(hex_byte) >>
+        g: call!(hex_byte) >>
+        b: call!(hex_byte) >>
+        (r, g, b)
+    )
+);
+
 named!(
     tag_value<&str, Item>,
     alt_complete!(
@@ -34,6 +54,15 @@ named!(
         tag!("i") => { |_| Item::AttrOn(Attr::Italic) } |
         tag!("u") => { |_| Item::AttrOn(Attr::Underline) } |
         tag!("a") => { |_| Item::AttrOn(Attr::Blink) } |
+        tag!("/b") => { |_| Item::AttrOff(Attr::Bold) } |
+        tag!("/i") => { |_| Item::AttrOff(Attr::Italic) } |
+        tag!("/u") => { |_| Item::AttrOff(Attr::Underline) } |
+        tag!("/a") => { |_| Item::AttrOff(Attr::Blink) } |
+        do_parse!(
+            fg: call!(hex_colour) >>
+            bg: opt!(preceded!(tag!(","), call!(hex_colour))) >>
+            tag!("}") >>
+            (fg, bg)) => { |(fg, bg): ((u8, u8, u8), Option<(u8, u8, u8)>)| Item::RgbColourOn(fg.0, fg.1, fg.2, bg) } |
         do_parse!(
             fg: map_res!(take_till1!(|c| c == '}'), i16::from_str) >>
             tag!("}") >>
@@ -74,9 +103,9 @@ named!(
     )
 );
 
-pub(crate) fn print_string(window: WINDOW, string: Vec<Item>) {
+pub(crate) fn print_string(renderer: &mut dyn Renderer, string: Vec<Item>) {
     for item in string {
-        item.print_window(window);
+        item.render(renderer);
     }
 }
 
@@ -85,10 +114,11 @@ pub(crate) fn finish_line(window: WINDOW, width: i32, colour: Option<(i16, i16)>
     let mut x: i32 = unsafe { mem::uninitialized() };
     getyx(window, &mut y, &mut x);
     let spaces = [' '].iter().cycle().take((width - x) as usize).collect::<String>();
+    let mut renderer = crate::renderer::CursesRenderer::new(window);
     if let Some(colour) = colour {
-        Item::ColourOn(colour.0, colour.1).print_window(window);
+        Item::ColourOn(colour.0, colour.1).render(&mut renderer);
     }
-    Item::Text(&spaces).print_window(window);
+    Item::Text(&spaces).render(&mut renderer);
 }
 
 #[cfg(test)]
@@ -112,6 +142,10 @@ mod tests {
         assert_eq!(parse_string_item("\x191}").unwrap().1, Item::ColourOn(1, -1));
         assert_eq!(parse_string_item("\x1933,41}").unwrap().1, Item::ColourOn(33, 41));
         assert_eq!(parse_string_item("\x1933,41,bu}").unwrap().1, Item::AttrOnEx(33, 41, { let mut set = EnumSet::new(); set.insert(Attr::Bold); set.insert(Attr::Underline); set }));
+        assert_eq!(parse_string_item("\x19/b").unwrap().1, Item::AttrOff(Attr::Bold));
+        assert_eq!(parse_string_item("\x19/i").unwrap().1, Item::AttrOff(Attr::Italic));
+        assert_eq!(parse_string_item("\x19/u").unwrap().1, Item::AttrOff(Attr::Underline));
+        assert_eq!(parse_string_item("\x19/a").unwrap().1, Item::AttrOff(Attr::Blink));
     }
 
     #[test]
@@ -123,4 +157,13 @@ mod tests {
     fn bold_string() {
         assert_eq!(parse_string("\x19bHello world!\x19o").unwrap().1, &[Item::AttrOn(Attr::Bold), Item::Text("Hello world!"), Item::AttrSet0]);
     }
+
+    #[test]
+    fn rgb_tag() {
+        assert_eq!(parse_string_item("\x19#ff8000}").unwrap().1, Item::RgbColourOn(0xff, 0x80, 0x00, None));
+        assert_eq!(
+            parse_string_item("\x19#ff8000,#001122}").unwrap().1,
+            Item::RgbColourOn(0xff, 0x80, 0x00, Some((0x00, 0x11, 0x22)))
+        );
+    }
 }
@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::env;
 use std::sync::Mutex;
 use std::mem;
 use enum_set::{EnumSet, CLike};
-use ncurses::{attr_t, A_BOLD, A_ITALIC, A_UNDERLINE, A_BLINK, init_pair, COLOR_PAIR, COLORS};
+use ncurses::{attr_t, A_BOLD, A_ITALIC, A_UNDERLINE, A_BLINK, init_extended_color, init_pair, COLOR_PAIR, COLOR_PAIRS, COLORS};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(u32)]
@@ -51,17 +52,63 @@ named!(
     )
 );
 
+/// Bounded LRU allocator for ncurses color pairs. Pair 0 is reserved by
+/// ncurses for the default colors, so we hand out 1..COLOR_PAIRS(). Once
+/// that range is exhausted, the least-recently-used `(fg, bg)` tuple is
+/// evicted and its slot is re-`init_pair`'d for the new tuple, instead of
+/// silently failing to allocate a pair like the old monotonic counter did.
+struct PairAllocator {
+    colours_to_pair: HashMap<(i16, i16), i16>,
+    pair_to_colours: HashMap<i16, (i16, i16)>,
+    lru: VecDeque<i16>,
+    next_pair: i16,
+}
+
+impl PairAllocator {
+    fn new() -> Self {
+        PairAllocator {
+            colours_to_pair: HashMap::new(),
+            pair_to_colours: HashMap::new(),
+            lru: VecDeque::new(),
+            next_pair: 1,
+        }
+    }
+
+    fn touch(&mut self, pair: i16) {
+        self.lru.retain(|&p| p != pair);
+        self.lru.push_back(pair);
+    }
+
+    fn get(&mut self, colours: (i16, i16)) -> i16 {
+        if let Some(&pair) = self.colours_to_pair.get(&colours) {
+            self.touch(pair);
+            return pair;
+        }
+        let pair = if (self.next_pair as i32) < COLOR_PAIRS() {
+            let pair = self.next_pair;
+            self.next_pair += 1;
+            pair
+        } else {
+            let evicted = self.lru.pop_front().expect("no color pair available to evict");
+            let evicted_colours = self.pair_to_colours.remove(&evicted).unwrap();
+            self.colours_to_pair.remove(&evicted_colours);
+            evicted
+        };
+        init_pair(pair, colours.0, colours.1);
+        self.colours_to_pair.insert(colours, pair);
+        self.pair_to_colours.insert(pair, colours);
+        self.touch(pair);
+        pair
+    }
+
+    /// How many pairs are currently in use, and how many ncurses can allocate in total.
+    fn pressure(&self) -> (usize, usize) {
+        (self.colours_to_pair.len(), COLOR_PAIRS() as usize)
+    }
+}
+
 lazy_static! {
-    // TODO: probably replace that mutex with an atomic.
-    static ref NEXT_PAIR: Mutex<i16> = Mutex::new(1);
-
-    /// a dict "color tuple -> color_pair"
-    /// Each time we use a color tuple, we check if it has already been used.
-    /// If not we create a new color_pair and keep it in that dict, to use it
-    /// the next time.
-    static ref COLOURS_DICT: Mutex<HashMap<(i16, i16), i16>> = {
-        Mutex::new(HashMap::new())
-    };
+    static ref PAIR_ALLOCATOR: Mutex<PairAllocator> = Mutex::new(PairAllocator::new());
 
     static ref TABLE_256_TO_16: Vec<u8> = vec![
          0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
@@ -91,18 +138,15 @@ fn colour_256_to_16(colour: i16) -> i16 {
 }
 
 fn get_pair(colours: (i16, i16)) -> attr_t {
-    let mut dict = COLOURS_DICT.lock().unwrap();
-    match dict.get(&colours) {
-        Some(val) => COLOR_PAIR(*val),
-        None => {
-            let mut pair_mut = NEXT_PAIR.lock().unwrap();
-            let pair = *pair_mut;
-            init_pair(pair, colours.0, colours.1);
-            dict.insert(colours, pair);
-            *pair_mut += 1;
-            COLOR_PAIR(pair)
-        }
-    }
+    let mut allocator = PAIR_ALLOCATOR.lock().unwrap();
+    COLOR_PAIR(allocator.get(colours))
+}
+
+/// `(in_use, capacity)`: how many color pairs are currently allocated, out
+/// of how many ncurses can hand out in total. Lets the theming layer warn
+/// (or throttle new distinct colors) as the allocator gets close to evicting.
+pub fn colour_pair_pressure() -> (usize, usize) {
+    PAIR_ALLOCATOR.lock().unwrap().pressure()
 }
 
 /// Takes a color tuple (as defined at the top of this file) and
@@ -127,6 +171,112 @@ pub fn curses_attr(mut colours: (i16, i16), mut attrs: EnumSet<Attr>) -> attr_t
     pair
 }
 
+const XTERM_BASE16: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+const XTERM_CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Returns the RGB value poezio's 256-color palette (16 base colors, the
+/// 6x6x6 cube, and the 24-step grayscale ramp) uses for a given index.
+fn xterm_256_rgb(index: u8) -> (u8, u8, u8) {
+    if index < 16 {
+        XTERM_BASE16[index as usize]
+    } else if index < 232 {
+        let i = index - 16;
+        let r = XTERM_CUBE_LEVELS[(i / 36) as usize];
+        let g = XTERM_CUBE_LEVELS[((i / 6) % 6) as usize];
+        let b = XTERM_CUBE_LEVELS[(i % 6) as usize];
+        (r, g, b)
+    } else {
+        let level = 8 + 10 * (index - 232);
+        (level, level, level)
+    }
+}
+
+/// Perceptual (luma-weighted) squared distance between two colors, used to
+/// pick the closest xterm-256 entry to a given RGB triple.
+fn colour_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> f64 {
+    let dr = a.0 as f64 - b.0 as f64;
+    let dg = a.1 as f64 - b.1 as f64;
+    let db = a.2 as f64 - b.2 as f64;
+    0.3 * dr * dr + 0.59 * dg * dg + 0.11 * db * db
+}
+
+fn nearest_xterm_256(rgb: (u8, u8, u8)) -> i16 {
+    (0u16..256)
+        .min_by(|&a, &b| {
+            colour_distance(rgb, xterm_256_rgb(a as u8))
+                .partial_cmp(&colour_distance(rgb, xterm_256_rgb(b as u8)))
+                .unwrap()
+        })
+        .unwrap() as i16
+}
+
+lazy_static! {
+    /// Cache of RGB triple -> nearest xterm-256 index, to avoid redoing the
+    /// distance computation for every glyph using the same color.
+    static ref RGB_TO_256_CACHE: Mutex<HashMap<(u8, u8, u8), i16>> = Mutex::new(HashMap::new());
+
+    static ref NEXT_EXTENDED_COLOUR: Mutex<i16> = Mutex::new(256);
+
+    /// Same idea as `PairAllocator`, but for `init_extended_color()` slots
+    /// allocated for exact truecolor values. These aren't recycled since
+    /// `init_extended_color()` slots are far more plentiful than color pairs.
+    static ref EXTENDED_COLOURS_DICT: Mutex<HashMap<(u8, u8, u8), i16>> = Mutex::new(HashMap::new());
+}
+
+fn rgb_to_256(rgb: (u8, u8, u8)) -> i16 {
+    let mut cache = RGB_TO_256_CACHE.lock().unwrap();
+    *cache.entry(rgb).or_insert_with(|| nearest_xterm_256(rgb))
+}
+
+fn get_extended_colour(rgb: (u8, u8, u8)) -> i16 {
+    let mut dict = EXTENDED_COLOURS_DICT.lock().unwrap();
+    if let Some(&colour) = dict.get(&rgb) {
+        return colour;
+    }
+    let mut next_mut = NEXT_EXTENDED_COLOUR.lock().unwrap();
+    let colour = *next_mut;
+    let scale = |c: u8| c as i32 * 1000 / 255;
+    init_extended_color(colour as i32, scale(rgb.0), scale(rgb.1), scale(rgb.2));
+    dict.insert(rgb, colour);
+    *next_mut += 1;
+    colour
+}
+
+/// Whether the terminal advertises 24-bit color support via `$COLORTERM`.
+fn has_truecolor() -> bool {
+    matches!(env::var("COLORTERM").as_deref(), Ok("truecolor") | Ok("24bit"))
+}
+
+/// Like [`curses_attr`], but takes exact RGB triples (as parsed from
+/// `\x19#rrggbb` theme markup) instead of indexed colors.
+///
+/// When the terminal supports truecolor, each color is allocated its own
+/// extended color slot via `init_extended_color()`. Otherwise the RGB value
+/// is downsampled to the nearest xterm-256 entry and fed through the usual
+/// 256-to-16 fallback in [`curses_attr`].
+///
+/// Returns the resolved indexed `(fg, bg)` pair alongside the curses attr,
+/// so callers that track active state (like [`crate::renderer::CursesRenderer`])
+/// can remember it instead of losing the RGB color on the next plain attr change.
+pub fn rgb_curses_attr(fg: (u8, u8, u8), bg: Option<(u8, u8, u8)>) -> ((i16, i16), attr_t) {
+    if has_truecolor() && COLORS() >= 256 {
+        let fg_colour = get_extended_colour(fg);
+        let bg_colour = bg.map(get_extended_colour).unwrap_or(-1);
+        ((fg_colour, bg_colour), get_pair((fg_colour, bg_colour)))
+    } else {
+        let fg_colour = rgb_to_256(fg);
+        let bg_colour = bg.map(rgb_to_256).unwrap_or(-1);
+        let colours = (fg_colour, bg_colour);
+        (colours, curses_attr(colours, EnumSet::new()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +309,17 @@ mod tests {
         let received = parse_attrs(attrs).unwrap();
         assert_eq!(received.1, expected);
     }
+
+    #[test]
+    fn nearest_256_exact_matches() {
+        // Exact palette hits resolve to the lowest matching index.
+        assert_eq!(nearest_xterm_256((0, 0, 0)), 0);
+        assert_eq!(nearest_xterm_256((255, 0, 0)), 9);
+    }
+
+    #[test]
+    fn nearest_256_grayscale_ramp() {
+        assert_eq!(xterm_256_rgb(232), (8, 8, 8));
+        assert_eq!(xterm_256_rgb(255), (238, 238, 238));
+    }
 }